@@ -1,4 +1,5 @@
 use regex_syntax::ast::{self, Ast};
+use regex_syntax::hir::{self, Hir};
 
 /// The results of analyzing AST of a regular expression (e.g., for supporting
 /// smart case).
@@ -8,6 +9,9 @@ pub(crate) struct AstAnalysis {
     any_uppercase: bool,
     /// True if and only if the regex contains any literal at all.
     any_literal: bool,
+    /// True only if every string matched by the regex must begin with a
+    /// literal `.`.
+    required_leading_dot: bool,
 }
 
 impl AstAnalysis {
@@ -26,7 +30,25 @@ impl AstAnalysis {
     /// Perform an AST analysis given the AST.
     pub(crate) fn from_ast(ast: &Ast) -> AstAnalysis {
         let mut analysis = AstAnalysis::new();
-        analysis.from_ast_impl(ast);
+        let _ = analysis.from_ast_impl(ast, false);
+        analysis.required_leading_dot = requires_leading_dot(ast);
+        analysis
+    }
+
+    /// Perform an analysis given the HIR translation of a pattern.
+    ///
+    /// Unlike `from_ast`, this sees the regex after translation, so it
+    /// correctly handles byte-oriented matchers (e.g. those built with
+    /// `allow_invalid_utf8(true)`) and sees escapes and class unions as
+    /// translation has resolved them. A class counts as uppercase when
+    /// any of its ranges starts or ends with an uppercase character, so
+    /// `\pL`, or `(?i)foo` after case folding, reports an uppercase
+    /// character here, unlike with `from_ast`. It does not compute
+    /// `requires_leading_dot`, since that's a property of the concrete
+    /// syntax the AST retains and that the HIR does not.
+    pub(crate) fn from_hir(hir: &Hir) -> AstAnalysis {
+        let mut analysis = AstAnalysis::new();
+        analysis.from_hir_impl(hir);
         analysis
     }
 
@@ -48,63 +70,105 @@ impl AstAnalysis {
         self.any_literal
     }
 
+    /// Returns true only if every string that the regex can match must
+    /// begin with a literal `.` character.
+    ///
+    /// For example, patterns like `\.git` or `\.a|\.b` report `true`, but
+    /// `\.*foo` reports `false` since the leading dot is optional.
+    ///
+    /// Only the first element after any empty items, inline flags and
+    /// `^`/`\A` anchors is inspected, so a pattern like `\b\.git` reports
+    /// `false` even though all of its matches begin with `.`.
+    pub(crate) fn requires_leading_dot(&self) -> bool {
+        self.required_leading_dot
+    }
+
     /// Creates a new `AstAnalysis` value with an initial configuration.
     fn new() -> AstAnalysis {
-        AstAnalysis { any_uppercase: false, any_literal: false }
+        AstAnalysis {
+            any_uppercase: false,
+            any_literal: false,
+            required_leading_dot: false,
+        }
     }
 
-    fn from_ast_impl(&mut self, ast: &Ast) {
+    /// Walks the AST, tracking whether we're currently in a scope made
+    /// case insensitive by an `(?i)`-style flag, so that literals inside
+    /// such a scope aren't reported as contributing an uppercase letter.
+    ///
+    /// Returns the `insensitive` scope in effect after `ast`, so that a
+    /// bare `(?i)`/`(?-i)` flag item can update the scope seen by whatever
+    /// follows it in the enclosing `Concat` or `Alternation`.
+    fn from_ast_impl(&mut self, ast: &Ast, insensitive: bool) -> bool {
         if self.done() {
-            return;
+            return insensitive;
         }
         match *ast {
-            Ast::Empty(_) => {}
-            Ast::Flags(_)
+            Ast::Empty(_)
             | Ast::Dot(_)
             | Ast::Assertion(_)
             | Ast::Class(ast::Class::Unicode(_))
-            | Ast::Class(ast::Class::Perl(_)) => {}
+            | Ast::Class(ast::Class::Perl(_)) => insensitive,
+            Ast::Flags(ref set) => apply_flags(insensitive, &set.flags),
             Ast::Literal(ref x) => {
-                self.from_ast_literal(x);
+                self.from_ast_literal(x, insensitive);
+                insensitive
             }
             Ast::Class(ast::Class::Bracketed(ref x)) => {
-                self.from_ast_class_set(&x.kind);
+                self.from_ast_class_set(&x.kind, insensitive);
+                insensitive
             }
             Ast::Repetition(ref x) => {
-                self.from_ast_impl(&x.ast);
+                self.from_ast_impl(&x.ast, insensitive);
+                insensitive
             }
             Ast::Group(ref x) => {
-                self.from_ast_impl(&x.ast);
+                self.from_ast_impl(&x.ast, group_insensitive(x, insensitive));
+                insensitive
             }
             Ast::Alternation(ref alt) => {
+                // A bare `(?i)` inside one branch is just a flag-setting
+                // item in the enclosing group/concat's sequence, so it
+                // carries over into the branches that follow it, exactly
+                // as it would for any other non-consuming item between
+                // branches. It only resets at the end of the enclosing
+                // group.
+                let mut insensitive = insensitive;
                 for x in &alt.asts {
-                    self.from_ast_impl(x);
+                    insensitive = self.from_ast_impl(x, insensitive);
                 }
+                insensitive
             }
-            Ast::Concat(ref alt) => {
-                for x in &alt.asts {
-                    self.from_ast_impl(x);
+            Ast::Concat(ref concat) => {
+                let mut insensitive = insensitive;
+                for x in &concat.asts {
+                    insensitive = self.from_ast_impl(x, insensitive);
                 }
+                insensitive
             }
         }
     }
 
-    fn from_ast_class_set(&mut self, ast: &ast::ClassSet) {
+    fn from_ast_class_set(&mut self, ast: &ast::ClassSet, insensitive: bool) {
         if self.done() {
             return;
         }
         match *ast {
             ast::ClassSet::Item(ref item) => {
-                self.from_ast_class_set_item(item);
+                self.from_ast_class_set_item(item, insensitive);
             }
             ast::ClassSet::BinaryOp(ref x) => {
-                self.from_ast_class_set(&x.lhs);
-                self.from_ast_class_set(&x.rhs);
+                self.from_ast_class_set(&x.lhs, insensitive);
+                self.from_ast_class_set(&x.rhs, insensitive);
             }
         }
     }
 
-    fn from_ast_class_set_item(&mut self, ast: &ast::ClassSetItem) {
+    fn from_ast_class_set_item(
+        &mut self,
+        ast: &ast::ClassSetItem,
+        insensitive: bool,
+    ) {
         if self.done() {
             return;
         }
@@ -114,26 +178,28 @@ impl AstAnalysis {
             | ast::ClassSetItem::Unicode(_)
             | ast::ClassSetItem::Perl(_) => {}
             ast::ClassSetItem::Literal(ref x) => {
-                self.from_ast_literal(x);
+                self.from_ast_literal(x, insensitive);
             }
             ast::ClassSetItem::Range(ref x) => {
-                self.from_ast_literal(&x.start);
-                self.from_ast_literal(&x.end);
+                self.from_ast_literal(&x.start, insensitive);
+                self.from_ast_literal(&x.end, insensitive);
             }
             ast::ClassSetItem::Bracketed(ref x) => {
-                self.from_ast_class_set(&x.kind);
+                self.from_ast_class_set(&x.kind, insensitive);
             }
             ast::ClassSetItem::Union(ref union) => {
                 for x in &union.items {
-                    self.from_ast_class_set_item(x);
+                    self.from_ast_class_set_item(x, insensitive);
                 }
             }
         }
     }
 
-    fn from_ast_literal(&mut self, ast: &ast::Literal) {
+    fn from_ast_literal(&mut self, ast: &ast::Literal, insensitive: bool) {
         self.any_literal = true;
-        self.any_uppercase = self.any_uppercase || ast.c.is_uppercase();
+        if !insensitive {
+            self.any_uppercase = self.any_uppercase || ast.c.is_uppercase();
+        }
     }
 
     /// Returns true if and only if the attributes can never change no matter
@@ -141,6 +207,169 @@ impl AstAnalysis {
     fn done(&self) -> bool {
         self.any_uppercase && self.any_literal
     }
+
+    fn from_hir_impl(&mut self, hir: &Hir) {
+        // The visitor returns `Err` only to stop early once `done()`, so
+        // there's nothing left to do with either outcome here.
+        let _ = hir::visit(hir, HirUppercaseVisitor { analysis: self });
+    }
+
+    fn from_hir_literal(&mut self, lit: &hir::Literal) {
+        self.any_literal = true;
+        if self.any_uppercase {
+            return;
+        }
+        self.any_uppercase = match *lit {
+            hir::Literal::Unicode(c) => c.is_uppercase(),
+            hir::Literal::Byte(b) => char::from(b).is_uppercase(),
+        };
+    }
+
+    fn from_hir_class_unicode(&mut self, cls: &hir::ClassUnicode) {
+        if self.any_uppercase {
+            return;
+        }
+        self.any_uppercase = cls
+            .ranges()
+            .iter()
+            .any(|r| r.start().is_uppercase() || r.end().is_uppercase());
+    }
+
+    fn from_hir_class_bytes(&mut self, cls: &hir::ClassBytes) {
+        if self.any_uppercase {
+            return;
+        }
+        self.any_uppercase = cls.ranges().iter().any(|r| {
+            char::from(r.start()).is_uppercase()
+                || char::from(r.end()).is_uppercase()
+        });
+    }
+}
+
+/// Drives `hir::visit` to feed every literal and class encountered in an
+/// `Hir` into the enclosing `AstAnalysis`.
+struct HirUppercaseVisitor<'a> {
+    analysis: &'a mut AstAnalysis,
+}
+
+impl<'a> hir::Visitor for HirUppercaseVisitor<'a> {
+    type Output = ();
+    // Returned by `visit_pre` to stop the walk early once `done()`; it
+    // never indicates an actual error.
+    type Err = ();
+
+    fn finish(self) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    fn visit_pre(&mut self, hir: &Hir) -> Result<(), Self::Err> {
+        if self.analysis.done() {
+            return Err(());
+        }
+        match *hir.kind() {
+            hir::HirKind::Literal(ref lit) => {
+                self.analysis.from_hir_literal(lit);
+            }
+            hir::HirKind::Class(hir::Class::Unicode(ref cls)) => {
+                self.analysis.from_hir_class_unicode(cls);
+            }
+            hir::HirKind::Class(hir::Class::Bytes(ref cls)) => {
+                self.analysis.from_hir_class_bytes(cls);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Returns the case-insensitive scope in effect at the start of `group`'s
+/// inner AST, given the scope `insensitive` that was in effect just before
+/// the group. A non-capturing group with its own inline flags, e.g.
+/// `(?i:...)`, establishes a new scope for its contents; every other kind
+/// of group just inherits the surrounding scope.
+fn group_insensitive(group: &ast::Group, insensitive: bool) -> bool {
+    match group.kind {
+        ast::GroupKind::NonCapturing(ref flags) => {
+            apply_flags(insensitive, flags)
+        }
+        _ => insensitive,
+    }
+}
+
+/// Applies the case-insensitivity flag items in `flags` to `insensitive`,
+/// returning the resulting state. Items before a negation turn the flag on;
+/// items after it turn the flag off.
+fn apply_flags(insensitive: bool, flags: &ast::Flags) -> bool {
+    let mut insensitive = insensitive;
+    let mut negated = false;
+    for item in &flags.items {
+        match item.kind {
+            ast::FlagsItemKind::Negation => negated = true,
+            ast::FlagsItemKind::Flag(ast::Flag::CaseInsensitive) => {
+                insensitive = !negated;
+            }
+            ast::FlagsItemKind::Flag(_) => {}
+        }
+    }
+    insensitive
+}
+
+/// Returns true only if every string that `ast` can match must begin with a
+/// literal `.` character, judged by its first element that is not skipped
+/// by `is_leading_dot_skippable`.
+fn requires_leading_dot(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Literal(ref x) => x.c == '.',
+        Ast::Group(ref x) => requires_leading_dot(&x.ast),
+        Ast::Repetition(ref x) => {
+            repetition_requires_at_least_one(&x.op.kind)
+                && requires_leading_dot(&x.ast)
+        }
+        Ast::Alternation(ref alt) => {
+            alt.asts.iter().all(requires_leading_dot)
+        }
+        Ast::Concat(ref concat) => {
+            for x in &concat.asts {
+                if is_leading_dot_skippable(x) {
+                    continue;
+                }
+                return requires_leading_dot(x);
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if `ast` is something that can appear before the first
+/// input-consuming element of a `Concat` without affecting whether a
+/// leading dot is required, e.g. empty sub-expressions, inline flags, and
+/// `^`/`\A` start-of-text anchors.
+fn is_leading_dot_skippable(ast: &Ast) -> bool {
+    match *ast {
+        Ast::Empty(_) | Ast::Flags(_) => true,
+        Ast::Assertion(ref x) => matches!(
+            x.kind,
+            ast::AssertionKind::StartLine | ast::AssertionKind::StartText
+        ),
+        _ => false,
+    }
+}
+
+/// Returns true if and only if the given repetition always matches its
+/// inner AST at least once.
+fn repetition_requires_at_least_one(kind: &ast::RepetitionKind) -> bool {
+    match *kind {
+        ast::RepetitionKind::ZeroOrOne | ast::RepetitionKind::ZeroOrMore => {
+            false
+        }
+        ast::RepetitionKind::OneOrMore => true,
+        ast::RepetitionKind::Range(ref range) => match *range {
+            ast::RepetitionRange::Exactly(n) => n >= 1,
+            ast::RepetitionRange::AtLeast(n) => n >= 1,
+            ast::RepetitionRange::Bounded(n, _) => n >= 1,
+        },
+    }
 }
 
 #[cfg(test)]
@@ -151,6 +380,16 @@ mod tests {
         AstAnalysis::from_pattern(pattern).unwrap()
     }
 
+    fn hir_analysis(pattern: &str) -> AstAnalysis {
+        let hir = regex_syntax::ParserBuilder::new()
+            .allow_invalid_utf8(true)
+            .unicode(false)
+            .build()
+            .parse(pattern)
+            .unwrap();
+        AstAnalysis::from_hir(&hir)
+    }
+
     #[test]
     fn various() {
         let x = analysis("");
@@ -213,4 +452,83 @@ mod tests {
         assert!(!x.any_uppercase);
         assert!(x.any_literal);
     }
+
+    #[test]
+    fn leading_dot() {
+        let x = analysis(r"\.git");
+        assert!(x.requires_leading_dot());
+
+        let x = analysis(r"(?:\.|x)y");
+        assert!(!x.requires_leading_dot());
+
+        let x = analysis(r"\.a|\.b");
+        assert!(x.requires_leading_dot());
+
+        let x = analysis(r"\.*foo");
+        assert!(!x.requires_leading_dot());
+
+        let x = analysis(r"^\.env");
+        assert!(x.requires_leading_dot());
+    }
+
+    #[test]
+    fn hir_uppercase() {
+        let x = hir_analysis("foo");
+        assert!(!x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = hir_analysis("Foo");
+        assert!(x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = hir_analysis(r"[a-z]");
+        assert!(!x.any_uppercase);
+        assert!(!x.any_literal);
+
+        let x = hir_analysis(r"[A-Z]");
+        assert!(x.any_uppercase);
+        assert!(!x.any_literal);
+
+        let x = hir_analysis(r"\xC0\xFF");
+        assert!(x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = hir_analysis(r"[\xC0-\xDF]");
+        assert!(x.any_uppercase);
+        assert!(!x.any_literal);
+    }
+
+    #[test]
+    fn case_insensitive_scopes() {
+        let x = analysis(r"(?i)Foo");
+        assert!(!x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = analysis(r"foo(?i:BAR)baz");
+        assert!(!x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = analysis(r"foo(?i:BAR)BAZ");
+        assert!(x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = analysis(r"(?i)(?-i)Foo");
+        assert!(x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = analysis(r"Foo(?i)bar");
+        assert!(x.any_uppercase);
+        assert!(x.any_literal);
+
+        // A bare `(?i)` isn't scoped to the alternation branch it appears
+        // in; it carries over into the branches that follow it, same as
+        // it would for any other item in the enclosing concat.
+        let x = analysis(r"(?i)Foo|BAR");
+        assert!(!x.any_uppercase);
+        assert!(x.any_literal);
+
+        let x = analysis(r"(?i)Foo|BARbaz");
+        assert!(!x.any_uppercase);
+        assert!(x.any_literal);
+    }
 }